@@ -0,0 +1,129 @@
+//! Deterministic dispatch support for `InstanceStore`.
+//!
+//! Message ordering and the swap-remove reshuffling in `receive_broadcast` are
+//! effectively nondeterministic across runs, which makes ordering bugs hard to
+//! reproduce. [`Determinism`] fixes the visitation order of bins and slots from
+//! a single seed and records every `(RawID, Fate)` decision, so a failing
+//! interleaving can be replayed exactly from its seed.
+//!
+//! On top of the recorded trace sits a "forbid parking" guard for tests: if a
+//! broadcast round makes no structural progress — no actor died or resized,
+//! whether because the store was empty or because the recipients just bounced
+//! the message around — while something is still expected to happen, the round
+//! panics with the trace instead of letting the simulation spin silently.
+
+use crate::id::RawID;
+use crate::messaging::Fate;
+
+/// A small SplitMix64 PRNG. It needs no external crate and is fully determined
+/// by its seed, which is all the deterministic dispatch order requires: the
+/// same seed must always reproduce the same permutation.
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    /// Seed the generator. The same seed always yields the same sequence, and
+    /// therefore the same visitation order.
+    pub fn seeded(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Permute `items` in place with a Fisher–Yates shuffle. For a given seed
+    /// the permutation is identical on every run, which is the only property
+    /// the deterministic visitation order depends on.
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        let mut i = items.len();
+        while i > 1 {
+            i -= 1;
+            let j = (self.next_u64() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+/// The deterministic-dispatch state an `InstanceStore` carries while in
+/// deterministic mode: the seeded `Rng` driving the visitation order, the guard
+/// flag, and the running trace of decisions.
+pub struct Determinism {
+    pub rng: Rng,
+    pub forbid_parking: bool,
+    pub trace: Vec<(RawID, Fate)>,
+}
+
+impl Determinism {
+    /// Enter deterministic mode seeded by `seed`. Parking detection is off until
+    /// [`Determinism::forbid_parking`] is called.
+    pub fn new(seed: u64) -> Determinism {
+        Determinism {
+            rng: Rng::seeded(seed),
+            forbid_parking: false,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Turn on the parking guard, so a round that makes no progress panics.
+    pub fn forbid_parking(&mut self) {
+        self.forbid_parking = true;
+    }
+
+    /// The `(RawID, Fate)` decisions recorded so far, in dispatch order.
+    pub fn trace(&self) -> &[(RawID, Fate)] {
+        &self.trace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_yields_same_sequence() {
+        let mut a = Rng::seeded(0xDEAD_BEEF);
+        let mut b = Rng::seeded(0xDEAD_BEEF);
+        for _ in 0..64 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn different_seeds_diverge() {
+        let mut a = Rng::seeded(1);
+        let mut b = Rng::seeded(2);
+        // The two streams must not be identical, or the seed wouldn't actually
+        // select the visitation order.
+        assert!((0..64).any(|_| a.next_u64() != b.next_u64()));
+    }
+
+    #[test]
+    fn shuffle_is_reproducible_from_the_seed() {
+        // This is exactly how `receive_broadcast_deterministic` fixes its
+        // visitation order: a seeded shuffle of the recipient list. The same
+        // seed must permute identically on every run so a failing interleaving
+        // replays exactly.
+        let order = |seed| {
+            let mut ids: Vec<u32> = (0..32).collect();
+            Rng::seeded(seed).shuffle(&mut ids);
+            ids
+        };
+        assert_eq!(order(7), order(7));
+        // A shuffle must actually reorder, not leave the list identity.
+        assert_ne!(order(7), (0..32).collect::<Vec<u32>>());
+    }
+
+    #[test]
+    fn forbid_parking_toggles_the_guard() {
+        let mut determinism = Determinism::new(0);
+        assert!(!determinism.forbid_parking);
+        determinism.forbid_parking();
+        assert!(determinism.forbid_parking);
+    }
+}
@@ -4,14 +4,94 @@ use crate::id::RawID;
 use crate::messaging::Fate;
 use super::ActorStateVTable;
 use compact::Compact;
+use std::path::Path;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
 mod slot_map;
-use self::slot_map::{SlotMap, SlotIndices};
+use self::slot_map::{SlotMap, SlotIndices, SlotError};
+
+mod journal;
+use self::journal::{Journal, Record};
+
+mod deterministic;
+use self::deterministic::Determinism;
+
+mod uuid;
+use self::uuid::Uuid;
+
+use std::collections::HashMap;
+
+/// How many slots a worker claims from a bin's cursor at a time in
+/// [`InstanceStore::receive_broadcast_parallel`]. Large enough to amortize the
+/// atomic fetch-add, small enough to keep the bins load-balanced.
+const SLOT_CLAIM: usize = 64;
+
+/// An `AtomicUsize` padded to its own cache line so per-bin cursors accessed by
+/// different workers don't false-share.
+#[repr(align(64))]
+struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    fn new(value: T) -> CachePadded<T> {
+        CachePadded { value }
+    }
+}
+
+/// A `*mut T` promised to be used for disjoint, non-overlapping access across
+/// the parallel broadcast workers; the opt-in contract on
+/// [`InstanceStore::receive_broadcast_parallel`] is what makes this sound.
+#[derive(Clone, Copy)]
+struct SendMut<T>(*mut T);
+unsafe impl<T> Send for SendMut<T> {}
+unsafe impl<T> Sync for SendMut<T> {}
+
+/// The immutable counterpart of [`SendMut`] for the broadcast packet and
+/// handler pointers shared read-only across workers.
+#[derive(Clone, Copy)]
+struct SendConst<T: ?Sized>(*const T);
+unsafe impl<T: ?Sized> Send for SendConst<T> {}
+unsafe impl<T: ?Sized> Sync for SendConst<T> {}
+
+/// What the serial fixup pass owes a recipient after the parallel pass has run
+/// its handler once. Collected per actor during the concurrent pass and applied
+/// afterwards so the arena and `slot_map` are only ever mutated single-threaded.
+enum Deferred {
+    /// `Fate::Die`: swap-remove the actor.
+    Die,
+    /// Non-compact `Fate::Live`: relocate (resize) the actor, which may `push`
+    /// into another bin.
+    Resize,
+    /// Compact `Fate::Live` under journaling: nothing structural, just persist
+    /// the in-place state delta.
+    Journal,
+}
 
 pub struct InstanceStore {
     instances: chunky::MultiArena<chunky::HeapHandler>,
     slot_map: SlotMap,
     pub n_instances: chunky::Value<usize, chunky::HeapHandler>,
+    /// When `Some`, every mutating operation is appended to this write-ahead
+    /// log (flushed before the arena mutation commits) so the store survives a
+    /// crash. `None` for the default, non-durable store.
+    journal: Option<Journal>,
+    /// When `Some`, dispatch runs in deterministic mode: bin/slot visitation
+    /// order is fixed by a seed and every decision is traced. `None` for the
+    /// default, nondeterministic store. Used by the test harness.
+    determinism: Option<Determinism>,
+    /// Reverse index from an actor's stable [`Uuid`] to its current `RawID`, so
+    /// a reference can be rebound after the actor is swap-removed, resized or
+    /// migrated to another machine. Updated whenever an actor is added,
+    /// imported or removed.
+    uuid_index: HashMap<Uuid, RawID>,
+    /// Hash of the stored type's name, forming the high bits of every `Uuid`
+    /// this store mints so distinct actor types never collide.
+    type_hash: u64,
+    /// Monotonic low bits of the next minted `Uuid`.
+    uuid_counter: u64,
 }
 
 const CHUNK_SIZE: usize = 1024 * 1024 * 16;
@@ -27,11 +107,201 @@ impl InstanceStore {
                 ),
                 n_instances: chunky::Value::load_or_default(ident.sub("n_instances"), 0),
                 slot_map: SlotMap::new(&ident.sub("slot_map")),
+                journal: None,
+                determinism: None,
+                uuid_index: HashMap::new(),
+                type_hash: fnv1a(type_name.as_bytes()),
+                uuid_counter: 0,
             }
     }
 
+    /// Open a journaled store, replaying the write-ahead log at `log_path` to
+    /// reconstruct the live actors from the last consistent checkpoint before
+    /// returning. Subsequent mutations append to the same log.
+    ///
+    /// Replay is idempotent with respect to the `slot_map` version counters:
+    /// the checkpoint restores them verbatim and the logical `add`/`remove`/
+    /// `resize` records only ever move already-compacted bytes around, so
+    /// recovering the same log twice yields identical `(id, version)`
+    /// associations.
+    ///
+    /// `state_v_table` is needed during replay to read each restored actor's
+    /// `RawID` back out of its `Compact` bytes, which is how the stable
+    /// `Uuid -> RawID` index is rebuilt so references survive the restart.
+    pub fn open_journaled<P: AsRef<Path>>(
+        type_name: &'static str,
+        typical_size: usize,
+        log_path: P,
+        state_v_table: &ActorStateVTable,
+    ) -> InstanceStore {
+        let mut store = InstanceStore::new(type_name, typical_size);
+        let mut journal = Journal::open(log_path).expect("could not open journal log");
+        let records = journal.recover().expect("could not recover journal log");
+        store.replay(records, state_v_table);
+        store.journal = Some(journal);
+        store
+    }
+
+    /// Rebuild the arena and slot map from a recovered record stream. When the
+    /// log holds a checkpoint, it comes first and establishes the base state;
+    /// the remainder are the mutations that followed it. A log that was never
+    /// checkpointed yields only mutations, replayed onto an empty store.
+    fn replay(&mut self, records: Vec<Record>, state_v_table: &ActorStateVTable) {
+        // `id -> (version, uuid, compacted bytes)` of every actor live at this
+        // point in the replay, plus the ids removed after the checkpoint so
+        // their slot_map version guard and free list can be re-established.
+        let mut live: HashMap<usize, (u8, u128, Vec<u8>)> = HashMap::new();
+        let mut removed: HashMap<usize, u8> = HashMap::new();
+        let mut counters: Option<Vec<u8>> = None;
+        // The UUID counter to resume minting from: the checkpoint's saved value,
+        // then advanced past every identity minted by a post-checkpoint `Add`.
+        // The low 64 bits of a `Uuid` are exactly the counter value it was
+        // minted from, so `1 + max(low64)` is the first counter no live actor
+        // already holds — restarting from 0 would re-mint colliding identities.
+        let mut uuid_counter = 0u64;
+
+        for record in records {
+            match record {
+                Record::Checkpoint { n_instances, uuid_counter: saved, slot_map, arena } => {
+                    live.clear();
+                    removed.clear();
+                    decode_arena_snapshot(&arena, &mut live);
+                    counters = Some(slot_map);
+                    uuid_counter = saved;
+                    *self.n_instances = n_instances;
+                }
+                Record::Add { id, version, uuid, state } => {
+                    removed.remove(&id);
+                    if uuid != 0 {
+                        uuid_counter = uuid_counter.max((uuid as u64).wrapping_add(1));
+                    }
+                    live.insert(id, (version, uuid, state));
+                }
+                Record::Resize { id, state } => {
+                    if let Some(entry) = live.get_mut(&id) {
+                        entry.2 = state;
+                    }
+                }
+                Record::Remove { id, version } => {
+                    if live.remove(&id).is_some() {
+                        removed.insert(id, version);
+                    }
+                }
+            }
+        }
+
+        if let Some(counters) = counters {
+            self.slot_map.restore_counters(&counters);
+        }
+
+        // Resume minting past every identity the recovered population already
+        // holds, so the next `add` can't hand out a `Uuid` that aliases a
+        // surviving actor.
+        self.uuid_counter = uuid_counter;
+
+        // `n_instances` is restored from the checkpoint; reconcile it with the
+        // post-checkpoint adds and removes so it matches the live population.
+        *self.n_instances = live.len();
+
+        for (id, (version, uuid, bytes)) in live {
+            let (slot_ptr, index) = self.instances.push(bytes.len());
+            unsafe {
+                slice::from_raw_parts_mut(slot_ptr as *mut u8, bytes.len())
+                    .copy_from_slice(&bytes);
+            }
+            self.slot_map.restore_entry(id, version, index.into());
+
+            // Re-establish the actor's stable identity so references held
+            // elsewhere still resolve after recovery. The `type_id`/`machine`
+            // come from the restored `Compact` bytes, but the `instance_id`/
+            // `version` are this store's local slot (an imported actor's bytes
+            // still embed its origin slot), so we rebuild the *local* RawID the
+            // actor answers to rather than the one baked into its state. A zero
+            // uuid marks an actor that predates the identity layer; leave it
+            // unindexed so unrelated identity-less actors can't collide.
+            if uuid != 0 {
+                let uuid = Uuid::from_u128(uuid);
+                let embedded = (state_v_table.get_raw_id)(slot_ptr as *mut ());
+                let raw_id = RawID::new(embedded.type_id, id as u32, embedded.machine, version);
+                self.slot_map.set_uuid(id, uuid);
+                self.uuid_index.insert(uuid, raw_id);
+            }
+        }
+
+        // Re-apply post-checkpoint removals so stale `RawID`s don't pass the
+        // version guard and the freed ids become reusable again.
+        for (id, version) in removed {
+            self.slot_map.restore_entry(id, version, SlotIndices::new(0, 0));
+            self.slot_map.free(id, version as usize);
+        }
+    }
+
+    /// Write a full, self-contained snapshot of the store to the log so future
+    /// recovery need not replay from the beginning of time.
+    pub fn checkpoint(&mut self, state_v_table: &ActorStateVTable) {
+        if self.journal.is_none() {
+            return;
+        }
+
+        let mut arena = Vec::new();
+        for (id, version, indices) in self.slot_map.live_entries() {
+            let ptr = self.at_index_mut(indices);
+            let size = (state_v_table.total_size_bytes)(ptr);
+            let bytes = unsafe { slice::from_raw_parts(ptr as *const u8, size) };
+            let uuid = self.slot_map.uuid_of(id).map(|uuid| uuid.as_u128()).unwrap_or(0);
+            encode_arena_entry(&mut arena, id, version, uuid, bytes);
+        }
+
+        let record = Record::Checkpoint {
+            n_instances: *self.n_instances,
+            uuid_counter: self.uuid_counter,
+            slot_map: self.slot_map.serialize(),
+            arena,
+        };
+        if let Some(ref mut journal) = self.journal {
+            journal
+                .checkpoint(&record)
+                .expect("could not append checkpoint");
+        }
+    }
+
+    fn journal_append(&mut self, record: Record) {
+        if let Some(ref mut journal) = self.journal {
+            journal.append(&record).expect("could not append journal record");
+        }
+    }
+
+    /// Journal the current bytes of a still-compact actor whose handler mutated
+    /// it in place (so no `add`/`resize` record was produced). A no-op unless
+    /// journaling is enabled.
+    fn journal_state_delta(&mut self, id: RawID, state_v_table: &ActorStateVTable) {
+        if self.journal.is_none() {
+            return;
+        }
+        if let Some(indices) = self.slot_map.indices_of(id.instance_id as usize, id.version) {
+            let ptr = self.at_index_mut(indices);
+            let size = (state_v_table.total_size_bytes)(ptr);
+            let bytes = unsafe { slice::from_raw_parts(ptr as *const u8, size).to_vec() };
+            self.journal_append(Record::Resize {
+                id: id.instance_id as usize,
+                state: bytes,
+            });
+        }
+    }
+
     fn allocate_instance_id(&mut self) -> (usize, usize) {
-        self.slot_map.allocate_id()
+        // Grow the slot map (doubling capacity) and retry rather than aborting
+        // when the table turns out to be too small, so long-running
+        // simulations churning through millions of ids keep going.
+        loop {
+            match self.slot_map.allocate_id() {
+                Ok(ids) => return ids,
+                Err(SlotError::InsufficientSlots { current, needed }) => {
+                    let grown = (current * 2).max(current + needed).max(1);
+                    self.slot_map.grow_to(grown);
+                }
+            }
+        }
     }
 
     fn at_index_mut(&mut self, index: SlotIndices) -> *mut () {
@@ -63,6 +333,34 @@ impl InstanceStore {
             .associate(id.instance_id as usize, index.into());
 
         (state_v_table.compact_behind)(initial_state, slot_ptr as *mut ());
+
+        // Mint a stable identity the first time this slot is populated (a resize
+        // re-adds an actor that already has one, so we leave it alone), and
+        // index it so the actor can be rebound by `Uuid` after it moves.
+        let uuid = match self.slot_map.uuid_of(id.instance_id as usize) {
+            Some(uuid) => uuid,
+            None => {
+                let uuid = self.mint_uuid();
+                self.slot_map.set_uuid(id.instance_id as usize, uuid);
+                self.uuid_index.insert(uuid, id);
+                uuid
+            }
+        };
+
+        // Journal the now-compacted, self-contained arena bytes (and flush)
+        // so recovery copies back a valid `Compact` representation rather than
+        // the pre-compaction source with its absolute pointers. The identity
+        // rides along so recovery rebuilds the `Uuid` index instead of minting
+        // a fresh one for the reloaded actor.
+        if self.journal.is_some() {
+            let bytes = slice::from_raw_parts(slot_ptr as *const u8, size).to_vec();
+            self.journal_append(Record::Add {
+                id: id.instance_id as usize,
+                version: id.version,
+                uuid: uuid.as_u128(),
+                state: bytes,
+            });
+        }
     }
 
     fn swap_remove(&mut self, indices: SlotIndices, state_v_table: &ActorStateVTable) -> bool {
@@ -85,10 +383,19 @@ impl InstanceStore {
     }
 
     fn remove_at_index(&mut self, i: SlotIndices, id: RawID, state_v_table: &ActorStateVTable) {
+        self.journal_append(Record::Remove {
+            id: id.instance_id as usize,
+            version: id.version,
+        });
         // TODO: not sure if this is the best place to drop actor state
         let old_actor_ptr = self.at_index_mut(i);
         (state_v_table.drop)(old_actor_ptr);
         self.swap_remove(i, state_v_table);
+        // Drop the dead actor's identity from the reverse index before the slot
+        // map clears it, so a stale `Uuid` can't resolve to a reused slot.
+        if let Some(uuid) = self.slot_map.uuid_of(id.instance_id as usize) {
+            self.uuid_index.remove(&uuid);
+        }
         self.slot_map
             .free(id.instance_id as usize, id.version as usize);
         *self.n_instances -= 1;
@@ -120,6 +427,11 @@ impl InstanceStore {
                 Fate::Live => {
                     if !is_still_compact {
                         self.resize(recipient_id.instance_id as usize, &state_v_table);
+                    } else {
+                        // In-place, still-compact mutation: the `add`/`resize`
+                        // paths didn't run, so journal the post-handler state
+                        // delta ourselves.
+                        self.journal_state_delta(recipient_id, state_v_table);
                     }
                 }
                 Fate::Die => self.remove(recipient_id, &state_v_table),
@@ -147,6 +459,17 @@ impl InstanceStore {
 
         for _ in 0..recipients_todo {
             let index = SlotIndices::new(bin_index, slot);
+            let id = (state_v_table.get_raw_id)(self.at_index_mut(index));
+
+            // An imported actor still carries its origin id (see
+            // `import_instance`), which keys the slot_map off a different slot.
+            // Dispatching it by that id would free or resize the wrong actor, so
+            // leave it inert under broadcast and move on.
+            if !self.slot_map.occupies(id.instance_id as usize, id.version, index) {
+                slot += 1;
+                continue;
+            }
+
             let (fate, is_still_compact, id) = {
                 let actor = self.at_index_mut(index);
                 let fate = handler(actor, packet_ptr, world);
@@ -156,6 +479,7 @@ impl InstanceStore {
             let repeat_slot = match fate {
                 Fate::Live => {
                     if is_still_compact {
+                        self.journal_state_delta(id, state_v_table);
                         false
                     } else {
                         self.resize_at_index(index, state_v_table);
@@ -192,4 +516,500 @@ impl InstanceStore {
         }
     }
 }
+
+    /// Like [`receive_broadcast`], but fans the independent per-bin loops out
+    /// across a worker pool. Each `chunky` bin is a contiguous arena whose
+    /// slots workers claim in disjoint ranges from a per-bin cursor, so every
+    /// slot is handled by exactly one worker (several may share a bin), and
+    /// handlers that keep the actor `is_still_compact` and return `Fate::Live`
+    /// touch only their own actor and run fully concurrently.
+    ///
+    /// The two cases that cannot be parallelized — `Fate::Die` (swap-remove)
+    /// and a non-compact `Fate::Live` (resize, which may `push` into another
+    /// bin) — are deferred into a pending queue during the parallel pass and
+    /// applied afterwards in a serial fixup pass. Every handler has already run
+    /// by then, so the fixup needs none of the re-dispatch bookkeeping of
+    /// [`receive_broadcast`]: it re-locates each actor through the `slot_map`
+    /// by id, which stays correct however earlier removals swapped slots.
+    ///
+    /// # Safety
+    ///
+    /// The handler is handed a shared `&World` (not `&mut World`) and runs on
+    /// many workers at once, so this entry point is only sound when the handler
+    /// neither spawns sub-actors nor mutates shared world state — callers opt in
+    /// precisely because they can guarantee that. Nothing here can check the
+    /// precondition, so — like [`add`], [`allocate_id`] and [`import_instance`],
+    /// whose pointer contracts are equally unverifiable — it is an `unsafe fn`:
+    /// the caller asserts the handler stays within its own bin. The narrower
+    /// handler type is what lets the fan-out be sound at all: every worker holds
+    /// a shared `&World`, of which any number may coexist, rather than aliasing
+    /// `&mut World`.
+    ///
+    /// Slot pointers are resolved single-threaded before the fan-out, so the
+    /// `&mut self` arena accessor is never called concurrently; because every
+    /// structural change (`Die`/non-compact `Live`) is deferred, no slot moves
+    /// during the parallel pass and those pointers stay valid throughout it.
+    ///
+    /// [`receive_broadcast`]: InstanceStore::receive_broadcast
+    /// [`add`]: InstanceStore::add
+    /// [`allocate_id`]: InstanceStore::allocate_id
+    /// [`import_instance`]: InstanceStore::import_instance
+    pub unsafe fn receive_broadcast_parallel(
+        &mut self,
+        packet_ptr: *const (),
+        world: &World,
+        handler: &Box<Fn(*mut (), *const (), &World) -> Fate>,
+        state_v_table: &ActorStateVTable,
+    ) {
+        let bins: Vec<(usize, usize)> =
+            self.instances.populated_bin_indices_and_lens().collect();
+        if bins.is_empty() {
+            return;
+        }
+
+        // Resolve a raw pointer to every recipient slot up front, single-
+        // threaded, so the `&mut self` accessor `at_mut` is never invoked from
+        // more than one thread. No slot is swap-removed or resized during the
+        // parallel pass (those are deferred), so these pointers remain valid and
+        // disjoint for its whole duration.
+        let slot_ptrs: Vec<Vec<SendMut<()>>> = bins
+            .iter()
+            .map(|&(bin_index, len)| {
+                (0..len)
+                    .filter_map(|slot| {
+                        let index = SlotIndices::new(bin_index, slot);
+                        let ptr = self.at_index_mut(index);
+                        // Leave imported actors (embedded id not mapping to
+                        // their slot) out of the fan-out entirely, so the
+                        // deferred fixup never keys off a stale id. See
+                        // `import_instance`.
+                        let id = (state_v_table.get_raw_id)(ptr);
+                        if self.slot_map.occupies(id.instance_id as usize, id.version, index) {
+                            Some(SendMut(ptr))
+                        } else {
+                            None
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // One cache-padded cursor per bin so workers claiming slot ranges in
+        // different bins never contend on the same cache line.
+        let cursors: Vec<CachePadded<AtomicUsize>> =
+            bins.iter().map(|_| CachePadded::new(AtomicUsize::new(0))).collect();
+
+        // Workers share bins via the per-bin cursors, so we don't cap to the
+        // bin count — a single large bin should still saturate every core.
+        let n_workers = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        let journaling = self.journal.is_some();
+
+        let world = SendConst(world as *const World);
+        let packet = SendConst(packet_ptr);
+        let handler = SendConst(handler as *const Box<Fn(*mut (), *const (), &World) -> Fate>);
+
+        // Parallel pass: run every handler exactly once, recording each actor's
+        // deferred decision. `Die` and non-compact `Live` need structural fixup;
+        // a compact `Live` needs nothing but a journal delta, and then only when
+        // journaling is on.
+        let cursors_ref = &cursors;
+        let slot_ptrs_ref = &slot_ptrs;
+        let state_v_table_ref: &ActorStateVTable = state_v_table;
+        let deferred: Vec<(RawID, Deferred)> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..n_workers)
+                .map(|_| {
+                    scope.spawn(move || {
+                        let mut out: Vec<(RawID, Deferred)> = Vec::new();
+                        let handler = unsafe { &*handler.0 };
+                        for (bin_slot, ptrs) in slot_ptrs_ref.iter().enumerate() {
+                            let len = ptrs.len();
+                            loop {
+                                let start = cursors_ref[bin_slot]
+                                    .value
+                                    .fetch_add(SLOT_CLAIM, Ordering::Relaxed);
+                                if start >= len {
+                                    break;
+                                }
+                                let end = (start + SLOT_CLAIM).min(len);
+                                for slot in start..end {
+                                    let actor = ptrs[slot].0;
+                                    let fate = handler(actor, packet.0, unsafe { &*world.0 });
+                                    let still_compact =
+                                        (state_v_table_ref.is_still_compact)(actor);
+                                    let id = (state_v_table_ref.get_raw_id)(actor);
+                                    match fate {
+                                        Fate::Live if still_compact => {
+                                            if journaling {
+                                                out.push((id, Deferred::Journal));
+                                            }
+                                        }
+                                        Fate::Live => out.push((id, Deferred::Resize)),
+                                        Fate::Die => out.push((id, Deferred::Die)),
+                                    }
+                                }
+                            }
+                        }
+                        out
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect()
+        });
+
+        // Serial fixup pass: apply the deferred removals and resizes, and
+        // journal the state deltas of the in-place mutations so a journaled
+        // store recovers the same population as the serial path. Each actor is
+        // re-located through the `slot_map` by id rather than by its now-stale
+        // `SlotIndices`, so the swap-in reshuffling that `swap_remove` performs
+        // can't make us operate on the wrong slot.
+        for (id, deferred) in deferred {
+            match deferred {
+                Deferred::Die => self.remove(id, state_v_table),
+                Deferred::Resize => {
+                    self.resize(id.instance_id as usize, state_v_table);
+                }
+                Deferred::Journal => self.journal_state_delta(id, state_v_table),
+            }
+        }
+    }
+
+    /// Enter deterministic dispatch mode, seeding the visitation order with
+    /// `seed`. While enabled, [`receive_broadcast_deterministic`] visits
+    /// recipients in a fixed, seed-derived order and records every decision,
+    /// so a failing interleaving replays exactly from the same seed.
+    ///
+    /// [`receive_broadcast_deterministic`]: InstanceStore::receive_broadcast_deterministic
+    pub fn enable_determinism(&mut self, seed: u64) {
+        self.determinism = Some(Determinism::new(seed));
+    }
+
+    /// Turn on the parking guard for the current deterministic run: a broadcast
+    /// round that makes no progress panics with the recorded trace. A no-op
+    /// unless deterministic mode is enabled.
+    pub fn forbid_parking(&mut self) {
+        if let Some(ref mut determinism) = self.determinism {
+            determinism.forbid_parking();
+        }
+    }
+
+    /// The `(RawID, Fate)` decisions recorded by deterministic dispatch so far,
+    /// in visitation order. Empty when deterministic mode is off.
+    pub fn dispatch_trace(&self) -> &[(RawID, Fate)] {
+        match self.determinism {
+            Some(ref determinism) => determinism.trace(),
+            None => &[],
+        }
+    }
+
+    /// Deterministic counterpart of [`receive_broadcast`]: instead of walking
+    /// bins and slots in arena order — which the swap-remove reshuffling makes
+    /// nondeterministic — it snapshots the live recipients, permutes them with
+    /// the seeded PRNG, and dispatches by `RawID` so the order is reproducible
+    /// regardless of how slots are later swapped around. Every decision is
+    /// appended to the trace.
+    ///
+    /// When the parking guard is armed ([`forbid_parking`]), a round that makes
+    /// no progress panics with the recorded trace rather than letting the system
+    /// spin silently. "Progress" means an observable structural change — an
+    /// actor died or a `Live` actor grew out of its slot (a resize). Two shapes
+    /// count as parked:
+    ///
+    /// - the broadcast found no recipients at all and the store is empty, so the
+    ///   sender waits forever on a reply that can never come; or
+    /// - recipients *were* dispatched yet none died or resized, i.e. the round
+    ///   bounced the message around without advancing — the livelock the guard
+    ///   exists to catch.
+    ///
+    /// An in-place, still-compact mutation is invisible here (the bytes may or
+    /// may not have changed), so an armed round is expected to make its progress
+    /// through a death or a resize; a run whose only forward motion is in-place
+    /// edits should not arm the guard.
+    ///
+    /// # Panics
+    ///
+    /// Panics if deterministic mode has not been enabled with
+    /// [`enable_determinism`], or if the parking guard fires.
+    ///
+    /// [`receive_broadcast`]: InstanceStore::receive_broadcast
+    /// [`forbid_parking`]: InstanceStore::forbid_parking
+    /// [`enable_determinism`]: InstanceStore::enable_determinism
+    pub fn receive_broadcast_deterministic(&mut self, packet_ptr: *const (), world: &mut World, handler: &Box<Fn(*mut(), *const (), &mut World) -> Fate>, state_v_table: &ActorStateVTable) {
+        let mut determinism = self
+            .determinism
+            .take()
+            .expect("deterministic dispatch requires enable_determinism first");
+
+        // Snapshot every live recipient up front, then fix the order with the
+        // seed. Dispatching by id afterwards means the swap-remove shuffle can't
+        // perturb who we visit or when.
+        let mut recipients: Vec<RawID> = Vec::new();
+        let bins: Vec<(usize, usize)> =
+            self.instances.populated_bin_indices_and_lens().collect();
+        for (bin_index, len) in bins {
+            for slot in 0..len {
+                let index = SlotIndices::new(bin_index, slot);
+                let id = (state_v_table.get_raw_id)(self.at_index_mut(index));
+                // Skip an imported actor whose embedded id doesn't map to the
+                // slot it occupies: dispatching by that id would visit a
+                // different local actor (see `import_instance`).
+                if self.slot_map.occupies(id.instance_id as usize, id.version, index) {
+                    recipients.push(id);
+                }
+            }
+        }
+        determinism.rng.shuffle(&mut recipients);
+
+        let mut consumed = 0usize;
+        // Track whether the round made observable structural progress; a round
+        // that dispatched to actors but neither killed nor resized any of them
+        // is a livelock, not merely the empty-store case.
+        let mut progressed = false;
+        for id in recipients {
+            let indices = match self
+                .slot_map
+                .indices_of(id.instance_id as usize, id.version)
+            {
+                // A recipient swap-removed earlier in this round is simply gone.
+                None => continue,
+                Some(indices) => indices,
+            };
+            let (fate, is_still_compact) = {
+                let actor = self.at_index_mut(indices);
+                let fate = handler(actor, packet_ptr, world);
+                (fate, (state_v_table.is_still_compact)(actor))
+            };
+            consumed += 1;
+
+            match fate {
+                Fate::Live => {
+                    determinism.trace.push((id, Fate::Live));
+                    if !is_still_compact {
+                        self.resize(id.instance_id as usize, state_v_table);
+                        progressed = true;
+                    } else {
+                        self.journal_state_delta(id, state_v_table);
+                    }
+                }
+                Fate::Die => {
+                    determinism.trace.push((id, Fate::Die));
+                    self.remove(id, state_v_table);
+                    progressed = true;
+                }
+            }
+        }
+
+        // Parked if the round made no structural progress: either nothing was
+        // dispatched into an empty store, or recipients were dispatched yet none
+        // died or resized. Checking only `consumed == 0 && n_instances == 0`
+        // would catch just the first case (a populated store always yields
+        // `consumed > 0`), so it could never flag a real livelock.
+        let parked = !progressed && (consumed > 0 || *self.n_instances == 0);
+        let forbid_parking = determinism.forbid_parking;
+        let trace_dump = if forbid_parking && parked {
+            Some(format_trace(&determinism.trace))
+        } else {
+            None
+        };
+        self.determinism = Some(determinism);
+
+        if let Some(trace) = trace_dump {
+            panic!(
+                "InstanceStore dispatch parked: a broadcast round made no \
+                 structural progress (no actor died or resized) while progress \
+                 was expected.\nrecorded trace:\n{}",
+                trace
+            );
+        }
+    }
+
+    fn mint_uuid(&mut self) -> Uuid {
+        let uuid = Uuid::mint(self.type_hash, self.uuid_counter);
+        self.uuid_counter += 1;
+        uuid
+    }
+
+    /// Serialize a live `Compact` actor for migration to another machine or a
+    /// checkpoint: its stable [`Uuid`] followed by its self-contained arena
+    /// bytes. The returned `Uuid` is also the one prefixed onto the byte buffer,
+    /// so the buffer alone round-trips through [`import_instance`].
+    ///
+    /// Returns `None` if `id` no longer resolves to a live actor.
+    ///
+    /// [`import_instance`]: InstanceStore::import_instance
+    pub fn export_instance(&mut self, id: RawID, state_v_table: &ActorStateVTable) -> Option<(Uuid, Vec<u8>)> {
+        let indices = self.slot_map.indices_of(id.instance_id as usize, id.version)?;
+        // Every live actor has an identity once it has been added; mint one
+        // lazily for anything that predates the identity layer.
+        let uuid = match self.slot_map.uuid_of(id.instance_id as usize) {
+            Some(uuid) => uuid,
+            None => {
+                let uuid = self.mint_uuid();
+                self.slot_map.set_uuid(id.instance_id as usize, uuid);
+                self.uuid_index.insert(uuid, id);
+                uuid
+            }
+        };
+
+        let ptr = self.at_index_mut(indices);
+        let size = (state_v_table.total_size_bytes)(ptr);
+        let mut bytes = Vec::with_capacity(uuid::UUID_BYTES + size);
+        bytes.extend_from_slice(&uuid.to_bytes());
+        unsafe {
+            bytes.extend_from_slice(slice::from_raw_parts(ptr as *const u8, size));
+        }
+        Some((uuid, bytes))
+    }
+
+    /// Re-home an actor exported by [`export_instance`] into this store: allocate
+    /// a fresh local slot, `Compact::compact_behind` the state into the arena,
+    /// and record the `Uuid -> new RawID` mapping so references elsewhere rebind
+    /// through [`resolve_uuid`]. The `instance_id`/`version` of the returned
+    /// `RawID` are local and so differ from the origin; the `machine` field is
+    /// carried over from the serialized state, and the `Uuid` is preserved.
+    ///
+    /// # Addressing an imported actor
+    ///
+    /// The state bytes are compacted in verbatim, so the `RawID` *embedded* in
+    /// them still carries the origin's `instance_id`/`version` — there is no
+    /// vtable hook to rewrite it in place. Address a migrated actor only through
+    /// the local `RawID` this returns (or [`resolve_uuid`]), which
+    /// [`receive_instance`] keys off the caller-supplied id and so handles
+    /// correctly. It must **not** be dispatched by its own embedded id: the
+    /// self-id broadcast paths read `get_raw_id` and key the `slot_map` off it,
+    /// which for an imported actor points at a different local slot (or none).
+    /// Those paths therefore skip any actor whose embedded id doesn't round-trip
+    /// to the slot it occupies (see [`SlotMap::occupies`]) rather than operating
+    /// on the wrong slot; a migrated actor stays inert under broadcast until a
+    /// vtable id-setter exists to rewrite its embedded `RawID`.
+    ///
+    /// [`export_instance`]: InstanceStore::export_instance
+    /// [`resolve_uuid`]: InstanceStore::resolve_uuid
+    /// [`receive_instance`]: InstanceStore::receive_instance
+    /// [`SlotMap::occupies`]: slot_map::SlotMap::occupies
+    pub unsafe fn import_instance(&mut self, bytes: &[u8], state_v_table: &ActorStateVTable) -> RawID {
+        let uuid = Uuid::from_bytes(bytes).expect("imported actor buffer is missing its uuid");
+        let mut state = bytes[uuid::UUID_BYTES..].to_vec();
+        let source = state.as_mut_ptr() as *mut ();
+
+        // Take the origin's type/machine from the serialized state, but a brand
+        // new local instance_id/version — that's what "re-allocate a local slot"
+        // means here.
+        let origin = (state_v_table.get_raw_id)(source);
+        let (instance_id, version) = self.allocate_instance_id();
+        let local_id = RawID::new(
+            origin.type_id,
+            instance_id as u32,
+            origin.machine,
+            version as u8,
+        );
+
+        let size = (state_v_table.total_size_bytes)(source);
+        let (slot_ptr, index) = self.instances.push(size);
+        self.slot_map.associate(instance_id, index.into());
+        (state_v_table.compact_behind)(source, slot_ptr as *mut ());
+        *self.n_instances += 1;
+
+        self.slot_map.set_uuid(instance_id, uuid);
+        self.uuid_index.insert(uuid, local_id);
+
+        // Journal the import just like `add`, carrying the preserved identity,
+        // so a migrated actor survives a later crash instead of vanishing
+        // because the log never recorded it.
+        if self.journal.is_some() {
+            let bytes = slice::from_raw_parts(slot_ptr as *const u8, size).to_vec();
+            self.journal_append(Record::Add {
+                id: instance_id,
+                version: version as u8,
+                uuid: uuid.as_u128(),
+                state: bytes,
+            });
+        }
+
+        local_id
+    }
+
+    /// Resolve a stable [`Uuid`] to the actor's current `RawID`, or `None` if no
+    /// live actor carries it here. This is how a reference survives the actor
+    /// being swap-removed, resized or migrated.
+    pub fn resolve_uuid(&self, uuid: Uuid) -> Option<RawID> {
+        self.uuid_index.get(&uuid).cloned()
+    }
+}
+
+/// FNV-1a hash of a type name, forming the high bits of every `Uuid` a store
+/// mints so actors of different types never share an identity.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+/// Render a recorded dispatch trace for a parking-guard panic, one decision per
+/// line. `Fate` is matched by hand so the dump doesn't depend on its `Debug`.
+fn format_trace(trace: &[(RawID, Fate)]) -> String {
+    let mut out = String::new();
+    for &(id, ref fate) in trace {
+        let decision = match *fate {
+            Fate::Live => "Live",
+            Fate::Die => "Die",
+        };
+        out.push_str(&format!(
+            "  instance {}.v{}@{} -> {}\n",
+            id.instance_id, id.version, id.machine, decision
+        ));
+    }
+    out
+}
+
+/// Append one `(id, version, uuid, bytes)` live-actor entry to a checkpoint's
+/// arena snapshot blob. The `uuid` rides along so recovery restores each
+/// actor's stable identity rather than minting a fresh one.
+fn encode_arena_entry(buf: &mut Vec<u8>, id: usize, version: u8, uuid: u128, bytes: &[u8]) {
+    buf.extend_from_slice(&(id as u64).to_le_bytes());
+    buf.push(version);
+    buf.extend_from_slice(&uuid.to_le_bytes());
+    buf.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+/// Fixed-size prefix of an arena entry: id (8) + version (1) + uuid (16) +
+/// payload length (8).
+const ARENA_ENTRY_HEADER: usize = 8 + 1 + 16 + 8;
+
+/// Decode a checkpoint's arena snapshot blob into `id -> (version, uuid, bytes)`.
+fn decode_arena_snapshot(
+    bytes: &[u8],
+    out: &mut ::std::collections::HashMap<usize, (u8, u128, Vec<u8>)>,
+) {
+    let mut cursor = 0;
+    while cursor + ARENA_ENTRY_HEADER <= bytes.len() {
+        let mut id_raw = [0u8; 8];
+        id_raw.copy_from_slice(&bytes[cursor..cursor + 8]);
+        cursor += 8;
+        let version = bytes[cursor];
+        cursor += 1;
+        let mut uuid_raw = [0u8; 16];
+        uuid_raw.copy_from_slice(&bytes[cursor..cursor + 16]);
+        cursor += 16;
+        let uuid = u128::from_le_bytes(uuid_raw);
+        let mut len_raw = [0u8; 8];
+        len_raw.copy_from_slice(&bytes[cursor..cursor + 8]);
+        cursor += 8;
+        let len = u64::from_le_bytes(len_raw) as usize;
+        if cursor + len > bytes.len() {
+            break;
+        }
+        let state = bytes[cursor..cursor + len].to_vec();
+        cursor += len;
+        out.insert(u64::from_le_bytes(id_raw) as usize, (version, uuid, state));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,278 @@
+use chunky;
+
+use super::uuid::Uuid;
+
+/// The physical location of an actor's state inside the backing
+/// `chunky::MultiArena`: which size-class bin it lives in and the slot it
+/// occupies within that bin. These move around as actors are swap-removed or
+/// resized, which is exactly why the `SlotMap` indirection exists.
+#[derive(Clone, Copy)]
+pub struct SlotIndices {
+    bin: u32,
+    slot: u32,
+}
+
+impl SlotIndices {
+    pub fn new(bin: usize, slot: usize) -> SlotIndices {
+        SlotIndices {
+            bin: bin as u32,
+            slot: slot as u32,
+        }
+    }
+
+    pub fn bin(&self) -> usize {
+        self.bin as usize
+    }
+
+    pub fn slot(&self) -> usize {
+        self.slot as usize
+    }
+}
+
+impl From<chunky::MultiArenaIndex> for SlotIndices {
+    fn from(index: chunky::MultiArenaIndex) -> SlotIndices {
+        SlotIndices::new(index.0, index.1)
+    }
+}
+
+impl From<SlotIndices> for chunky::MultiArenaIndex {
+    fn from(indices: SlotIndices) -> chunky::MultiArenaIndex {
+        chunky::MultiArenaIndex(indices.bin(), indices.slot())
+    }
+}
+
+/// Why a fresh `instance_id` couldn't be handed out: the slot table is full
+/// and `needed` more entries would have to be made available (by growing it)
+/// before allocation can succeed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotError {
+    InsufficientSlots { current: usize, needed: usize },
+}
+
+/// Maps a stable `instance_id` to the ever-shifting `SlotIndices` of the actor
+/// in the arena, guarding every lookup with a per-slot version counter so a
+/// `RawID` that outlived its actor resolves to `None` instead of aliasing a
+/// reused slot.
+pub struct SlotMap {
+    entries: Vec<SlotIndices>,
+    last_known_version: Vec<u8>,
+    free_ids_with_versions: Vec<(usize, usize)>,
+    /// The stable identity of the actor occupying each slot, `None` for a slot
+    /// that has never been associated or whose actor has been freed. Kept in
+    /// lock-step with `entries` so a `Uuid` outlives the `SlotIndices` churn of
+    /// swap-remove and resize, and is cleared on `free` so a reused id starts
+    /// out without a stale identity.
+    uuids: Vec<Option<Uuid>>,
+    /// How many distinct ids the table may hand out before it must grow.
+    capacity: usize,
+}
+
+impl SlotMap {
+    pub fn new(_ident: &chunky::Ident) -> SlotMap {
+        SlotMap {
+            entries: Vec::new(),
+            last_known_version: Vec::new(),
+            free_ids_with_versions: Vec::new(),
+            uuids: Vec::new(),
+            capacity: 0,
+        }
+    }
+
+    /// Hand out the next free `(id, version)`, reusing a freed slot when one is
+    /// available. Returns [`SlotError::InsufficientSlots`] instead of panicking
+    /// when the table is exhausted so the caller can grow it and retry.
+    pub fn allocate_id(&mut self) -> Result<(usize, usize), SlotError> {
+        if let Some((id, version)) = self.free_ids_with_versions.pop() {
+            return Ok((id, version));
+        }
+        if self.entries.len() >= self.capacity {
+            return Err(SlotError::InsufficientSlots {
+                current: self.capacity,
+                needed: self.entries.len() + 1 - self.capacity,
+            });
+        }
+        let id = self.entries.len();
+        self.entries.push(SlotIndices::new(0, 0));
+        self.last_known_version.push(0);
+        self.uuids.push(None);
+        Ok((id, 0))
+    }
+
+    /// Raise the capacity of the slot table, preserving every existing
+    /// `(id, version)` association and the free list untouched.
+    pub fn grow_to(&mut self, new_capacity: usize) {
+        if new_capacity <= self.capacity {
+            return;
+        }
+        let additional = new_capacity - self.entries.len();
+        self.entries.reserve(additional);
+        self.last_known_version.reserve(additional);
+        self.uuids.reserve(additional);
+        self.capacity = new_capacity;
+    }
+
+    pub fn associate(&mut self, id: usize, new_indices: SlotIndices) {
+        self.entries[id] = new_indices;
+    }
+
+    pub fn indices_of(&self, id: usize, version: u8) -> Option<SlotIndices> {
+        if self.last_known_version[id] == version {
+            Some(self.entries[id])
+        } else {
+            None
+        }
+    }
+
+    pub fn indices_of_no_version_check(&self, id: usize) -> Option<SlotIndices> {
+        self.entries.get(id).cloned()
+    }
+
+    /// Whether the actor keyed by `(id, version)` currently occupies exactly
+    /// `indices`. Used to gate self-id broadcast dispatch: an actor whose
+    /// embedded `RawID` doesn't round-trip to the slot it actually sits in (an
+    /// imported actor still carrying its origin id) must not have structural
+    /// operations keyed off that stale id, or they'd free or resize the wrong
+    /// slot.
+    pub fn occupies(&self, id: usize, version: u8, indices: SlotIndices) -> bool {
+        // An origin id can exceed the local table, so bounds-check rather than
+        // indexing `last_known_version`/`entries` directly the way `indices_of`
+        // does — the whole point of this gate is to stay safe for ids that were
+        // never allocated here.
+        if self.last_known_version.get(id).cloned() != Some(version) {
+            return false;
+        }
+        let found = self.entries[id];
+        found.bin == indices.bin && found.slot == indices.slot
+    }
+
+    pub fn free(&mut self, id: usize, version: usize) {
+        self.last_known_version[id] = (version + 1) as u8;
+        self.free_ids_with_versions.push((id, version + 1));
+        // Drop the dead actor's identity so the next actor that reuses this id
+        // doesn't inherit a stale `Uuid`.
+        self.uuids[id] = None;
+    }
+
+    /// Record the stable identity of the actor occupying `id`.
+    pub fn set_uuid(&mut self, id: usize, uuid: Uuid) {
+        self.uuids[id] = Some(uuid);
+    }
+
+    /// The stable identity of the actor occupying `id`, if one has been set.
+    pub fn uuid_of(&self, id: usize) -> Option<Uuid> {
+        self.uuids.get(id).and_then(|uuid| *uuid)
+    }
+
+    /// The `(id, version, indices)` of every currently live actor, i.e. every
+    /// allocated slot that isn't on the free list. Used when checkpointing.
+    pub fn live_entries(&self) -> Vec<(usize, u8, SlotIndices)> {
+        let free: ::std::collections::HashSet<usize> =
+            self.free_ids_with_versions.iter().map(|&(id, _)| id).collect();
+        (0..self.entries.len())
+            .filter(|id| !free.contains(id))
+            .map(|id| (id, self.last_known_version[id], self.entries[id]))
+            .collect()
+    }
+
+    /// Serialize the version counters, free list and per-slot identities so a
+    /// checkpoint can restore them verbatim; the `entries` themselves are
+    /// rebuilt from the arena snapshot and so aren't stored here. The `uuids`
+    /// are persisted so a recovered actor keeps the identity it was minted with
+    /// rather than being handed a fresh one.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&self.last_known_version);
+        buf.extend_from_slice(&(self.free_ids_with_versions.len() as u64).to_le_bytes());
+        for &(id, version) in &self.free_ids_with_versions {
+            buf.extend_from_slice(&(id as u64).to_le_bytes());
+            buf.extend_from_slice(&(version as u64).to_le_bytes());
+        }
+        for uuid in &self.uuids {
+            match *uuid {
+                Some(uuid) => {
+                    buf.push(1);
+                    buf.extend_from_slice(&uuid.as_u128().to_le_bytes());
+                }
+                None => buf.push(0),
+            }
+        }
+        buf
+    }
+
+    /// Reset to the version counters, free list and identities captured by
+    /// [`serialize`], clearing all slot associations (the caller re-`associate`s
+    /// live actors as it reloads them into the arena).
+    ///
+    /// [`serialize`]: SlotMap::serialize
+    pub fn restore_counters(&mut self, bytes: &[u8]) {
+        let mut cursor = 0;
+        let n = read_u64(bytes, &mut cursor) as usize;
+        self.entries = vec![SlotIndices::new(0, 0); n];
+        self.capacity = n;
+        self.last_known_version = bytes[cursor..cursor + n].to_vec();
+        cursor += n;
+        let free_len = read_u64(bytes, &mut cursor) as usize;
+        self.free_ids_with_versions = Vec::with_capacity(free_len);
+        for _ in 0..free_len {
+            let id = read_u64(bytes, &mut cursor) as usize;
+            let version = read_u64(bytes, &mut cursor) as usize;
+            self.free_ids_with_versions.push((id, version));
+        }
+        // Identities are appended after the free list. Tolerate a torn tail
+        // (a checkpoint lost mid-write): a missing tag, or a present-tag whose
+        // 16-byte value didn't make it to disk, defaults to `None` rather than
+        // indexing out of bounds.
+        self.uuids = Vec::with_capacity(n);
+        for _ in 0..n {
+            match bytes.get(cursor) {
+                Some(&1) if cursor + 1 + 16 <= bytes.len() => {
+                    cursor += 1;
+                    let raw = read_u128(bytes, &mut cursor);
+                    self.uuids.push(Some(Uuid::from_u128(raw)));
+                }
+                Some(&0) => {
+                    cursor += 1;
+                    self.uuids.push(None);
+                }
+                _ => self.uuids.push(None),
+            }
+        }
+    }
+}
+
+impl SlotMap {
+    /// Re-establish the slot association for a live actor while reloading it
+    /// into the arena during recovery, growing the backing vectors if the
+    /// checkpoint's counters didn't already cover this id.
+    pub fn restore_entry(&mut self, id: usize, version: u8, indices: SlotIndices) {
+        while self.entries.len() <= id {
+            self.entries.push(SlotIndices::new(0, 0));
+            self.last_known_version.push(0);
+            self.uuids.push(None);
+        }
+        if self.capacity < self.entries.len() {
+            self.capacity = self.entries.len();
+        }
+        // A restored-as-live id must not also linger on the free list (it may
+        // have been freed before the checkpoint and reused after it), or the
+        // next `allocate_id` would hand out a still-live slot.
+        self.free_ids_with_versions.retain(|&(free_id, _)| free_id != id);
+        self.entries[id] = indices;
+        self.last_known_version[id] = version;
+    }
+}
+
+fn read_u64(bytes: &[u8], cursor: &mut usize) -> u64 {
+    let mut raw = [0u8; 8];
+    raw.copy_from_slice(&bytes[*cursor..*cursor + 8]);
+    *cursor += 8;
+    u64::from_le_bytes(raw)
+}
+
+fn read_u128(bytes: &[u8], cursor: &mut usize) -> u128 {
+    let mut raw = [0u8; 16];
+    raw.copy_from_slice(&bytes[*cursor..*cursor + 16]);
+    *cursor += 16;
+    u128::from_le_bytes(raw)
+}
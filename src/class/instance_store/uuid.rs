@@ -0,0 +1,78 @@
+//! A minimal 128-bit stable identity for actors.
+//!
+//! A `RawID` encodes `machine`/`instance_id`/`version`, all of which are
+//! arena-local and change when an actor is swap-removed, resized or migrated to
+//! another machine. A [`Uuid`] is minted once and then travels with the actor's
+//! state, so references held elsewhere can be rebound through
+//! `InstanceStore::resolve_uuid` after any of those `RawID` fields change.
+
+/// A 128-bit actor identity, stable for the lifetime of one logical actor even
+/// as its `RawID` is re-homed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Uuid(u128);
+
+/// Number of bytes in a serialized [`Uuid`].
+pub const UUID_BYTES: usize = 16;
+
+impl Uuid {
+    /// Mint an identity from a per-store type hash (high 64 bits) and a
+    /// monotonically increasing per-store counter (low 64 bits). Distinct actor
+    /// types and distinct instances within a store never collide; minting needs
+    /// no clock or OS entropy, so it stays deterministic and dependency-free.
+    pub fn mint(type_hash: u64, counter: u64) -> Uuid {
+        Uuid(((type_hash as u128) << 64) | counter as u128)
+    }
+
+    /// The 16-byte little-endian wire form appended ahead of an actor's state
+    /// by `InstanceStore::export_instance`.
+    pub fn to_bytes(&self) -> [u8; UUID_BYTES] {
+        self.0.to_le_bytes()
+    }
+
+    /// Wrap the raw 128-bit value carried through the journal's `Add`/checkpoint
+    /// records so recovery can re-establish an actor's identity.
+    pub fn from_u128(raw: u128) -> Uuid {
+        Uuid(raw)
+    }
+
+    /// The raw 128-bit value, for persisting the identity in a journal record.
+    pub fn as_u128(&self) -> u128 {
+        self.0
+    }
+
+    /// Recover a `Uuid` from the leading bytes written by [`Uuid::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Option<Uuid> {
+        if bytes.len() < UUID_BYTES {
+            return None;
+        }
+        let mut raw = [0u8; UUID_BYTES];
+        raw.copy_from_slice(&bytes[..UUID_BYTES]);
+        Some(Uuid(u128::from_le_bytes(raw)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mint_is_unique_per_counter_and_type() {
+        let a0 = Uuid::mint(1, 0);
+        let a1 = Uuid::mint(1, 1);
+        let b0 = Uuid::mint(2, 0);
+        assert_ne!(a0, a1);
+        assert_ne!(a0, b0);
+    }
+
+    #[test]
+    fn byte_and_u128_forms_round_trip() {
+        let uuid = Uuid::mint(0xABCD, 7);
+        assert_eq!(Uuid::from_bytes(&uuid.to_bytes()), Some(uuid));
+        assert_eq!(Uuid::from_u128(uuid.as_u128()), uuid);
+    }
+
+    #[test]
+    fn from_bytes_rejects_short_input() {
+        assert_eq!(Uuid::from_bytes(&[0u8; UUID_BYTES - 1]), None);
+    }
+}